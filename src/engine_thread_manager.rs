@@ -1,6 +1,21 @@
 use std::str::SplitWhitespace;
 use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
 use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use color::Color;
+use movegen::move_to_uci;
+use perft;
+use piece::PieceType;
+use position;
+use position::Position;
+use search;
+
+// Used when no "depth" is given and no time control limits the search either; keeps a toy engine
+// from searching forever on a bare "go".
+const DEFAULT_MAX_DEPTH: u32 = 5;
 
 pub struct EngineThreadManager {
     tx: mpsc::Sender<MessageToEngine>
@@ -9,20 +24,58 @@ pub struct EngineThreadManager {
 pub enum MessageToEngine {
     IsReady,
     PonderHit,
-    Stop
+    Stop,
+    Position(Position),
+    Go {
+        wtime: Option<u64>,
+        btime: Option<u64>,
+        winc: Option<u64>,
+        binc: Option<u64>,
+        movetime: Option<u64>,
+        depth: Option<u32>
+    }
 }
 
 impl EngineThreadManager {
     pub fn new() -> EngineThreadManager {
         let (tx, rx) = mpsc::channel::<MessageToEngine>();
-        // XXX create the thread
+        thread::spawn(move || run_engine_thread(rx));
         EngineThreadManager {
             tx: tx
         }
     }
 
     pub fn cmd_go(&self, tokens: &mut SplitWhitespace) {
-        // XXX
+        let mut tokens = tokens.peekable();
+        if tokens.peek() == Some(&"perft") {
+            tokens.next();
+            match tokens.next().and_then(|depth| depth.parse::<u32>().ok()) {
+                Some(depth) => cmd_go_perft(depth),
+                None => ()    // Missing or non-numeric depth; ignore the malformed command
+            }
+            return;
+        }
+
+        let mut wtime = None;
+        let mut btime = None;
+        let mut winc = None;
+        let mut binc = None;
+        let mut movetime = None;
+        let mut depth = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                "wtime" => wtime = tokens.next().and_then(|t| t.parse().ok()),
+                "btime" => btime = tokens.next().and_then(|t| t.parse().ok()),
+                "winc" => winc = tokens.next().and_then(|t| t.parse().ok()),
+                "binc" => binc = tokens.next().and_then(|t| t.parse().ok()),
+                "movetime" => movetime = tokens.next().and_then(|t| t.parse().ok()),
+                "depth" => depth = tokens.next().and_then(|t| t.parse().ok()),
+                _ => ()    // "ponder", "infinite", "searchmoves", etc. are not supported yet
+            }
+        }
+        self.tx.send(MessageToEngine::Go { wtime: wtime, btime: btime, winc: winc, binc: binc,
+                                            movetime: movetime, depth: depth })
+            .expect("Error sending message");
     }
 
     pub fn cmd_isready(&self) {
@@ -34,14 +87,131 @@ impl EngineThreadManager {
     }
 
     pub fn cmd_position(&self, tokens: &mut SplitWhitespace) {
-        // XXX
+        let mut pos = match tokens.next() {
+            Some("startpos") => {
+                tokens.next();    // Skip the "moves" keyword, if present
+                Position::new()
+            },
+            Some("fen") => {
+                let fen_tokens: Vec<&str> = tokens.by_ref().take_while(|&t| t != "moves").collect();
+                match Position::from_fen(&fen_tokens.join(" ")) {
+                    Ok(pos) => pos,
+                    Err(_) => return    // Malformed FEN; ignore the command
+                }
+            },
+            _ => return    // Malformed command; ignore it
+        };
+        for uci_move in tokens {
+            apply_uci_move(&mut pos, uci_move);
+        }
+        self.tx.send(MessageToEngine::Position(pos)).expect("Error sending message");
     }
 
-    pub fn cmd_setoption(&self, tokens: &mut SplitWhitespace) {
-        // XXX
+    pub fn cmd_setoption(&self, _tokens: &mut SplitWhitespace) {
+        // No options are supported yet, so there's nothing to configure.
     }
 
     pub fn cmd_stop(&self) {
         self.tx.send(MessageToEngine::Stop).expect("Error sending message");
     }
 }
+
+fn apply_uci_move(pos: &mut Position, uci_move: &str) {
+    let src = position::parse_algebraic_coords(&uci_move[0..2]);
+    let dst = position::parse_algebraic_coords(&uci_move[2..4]);
+    let promotion = uci_move.chars().nth(4).map(|piece_char| match piece_char {
+        'q' => PieceType::Queen,
+        'r' => PieceType::Rook,
+        'b' => PieceType::Bishop,
+        'n' => PieceType::Knight,
+        _ => panic!("Invalid promotion piece in UCI move: {}", uci_move)
+    });
+    pos.make_move(src, dst, promotion);
+}
+
+// Prints a standard "divide" breakdown followed by the total node count, the usual way to
+// validate (or localize a bug in) the move generator from the UCI console.
+// TODO: run this from whatever position "position" last set up, once that's tracked here too.
+fn cmd_go_perft(depth: u32) {
+    let mut pos = Position::new();
+    let mut total_nodes = 0;
+    for (mv, nodes) in perft::divide(&mut pos, depth) {
+        println!("{}: {}", move_to_uci(mv), nodes);
+        total_nodes += nodes;
+    }
+    println!();
+    println!("Nodes searched: {}", total_nodes);
+}
+
+fn run_engine_thread(rx: Receiver<MessageToEngine>) {
+    let mut pos = Position::new();
+    for msg in rx.iter() {
+        match msg {
+            MessageToEngine::IsReady => println!("readyok"),
+            MessageToEngine::PonderHit => (),             // Pondering is not implemented
+            MessageToEngine::Stop => (),                  // No search is running; nothing to do
+            MessageToEngine::Position(new_pos) => pos = new_pos,
+            MessageToEngine::Go { wtime, btime, winc, binc, movetime, depth } => {
+                run_go(&mut pos, &rx, wtime, btime, winc, binc, movetime, depth);
+            }
+        }
+    }
+}
+
+// Runs the search and prints "bestmove". While the search is running, `rx.try_recv()` drains the
+// channel: a "stop" message interrupts the search, "isready" is answered with "readyok" right
+// away (GUIs rely on this during search to confirm the engine is alive), and anything else
+// (there shouldn't be anything else mid-search per the UCI spec) is simply discarded rather than
+// left on the channel.
+fn run_go(pos: &mut Position,
+          rx: &Receiver<MessageToEngine>,
+          wtime: Option<u64>,
+          btime: Option<u64>,
+          winc: Option<u64>,
+          binc: Option<u64>,
+          movetime: Option<u64>,
+          depth: Option<u32>) {
+    let deadline = search_deadline(pos, wtime, btime, winc, binc, movetime);
+    let max_depth = match (depth, deadline) {
+        (Some(depth), _) => depth,
+        (None, Some(_)) => u32::max_value(),    // The deadline ends the search
+        (None, None) => DEFAULT_MAX_DEPTH
+    };
+    let best_move = search::search(pos, max_depth, || {
+        let mut stop_requested = false;
+        while let Ok(msg) = rx.try_recv() {
+            match msg {
+                MessageToEngine::Stop => stop_requested = true,
+                MessageToEngine::IsReady => println!("readyok"),
+                _ => ()
+            }
+        }
+        stop_requested || deadline.map_or(false, |deadline| Instant::now() >= deadline)
+    });
+    match best_move {
+        Some(mv) => println!("bestmove {}", move_to_uci(mv)),
+        None => println!("bestmove 0000")    // No legal moves; UCI has no "resign" message
+    }
+}
+
+// Picks a search deadline from the UCI time-control parameters, if any were given. `movetime`
+// (an explicit instruction for this move only) takes priority; otherwise this is a simple
+// fixed-fraction allocation of the remaining clock, not real time management.
+fn search_deadline(pos: &Position,
+                    wtime: Option<u64>,
+                    btime: Option<u64>,
+                    winc: Option<u64>,
+                    binc: Option<u64>,
+                    movetime: Option<u64>) -> Option<Instant> {
+    if let Some(movetime) = movetime {
+        return Some(Instant::now() + Duration::from_millis(movetime));
+    }
+    let (time_left, increment) = match pos.side_to_move() {
+        Color::White => (wtime, winc),
+        Color::Black => (btime, binc)
+    };
+    time_left.map(|time_left| {
+        let budget_millis = time_left / 20 + increment.unwrap_or(0) / 2;
+        Instant::now() + Duration::from_millis(budget_millis)
+    })
+}
@@ -3,8 +3,12 @@ extern crate regex;
 
 mod color;
 mod engine_thread_manager;
+mod movegen;
+mod perft;
 mod piece;
 mod position;
+mod search;
+mod zobrist;
 
 fn main() {
     let engine_mgr = engine_thread_manager::EngineThreadManager::new();
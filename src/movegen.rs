@@ -0,0 +1,363 @@
+use color::Color;
+use color::opposite_color;
+use piece::PieceType;
+use position::can_piece_move_into_cell;
+use position::Cell;
+use position::Position;
+
+const ROW_1: usize = 7;
+const ROW_2: usize = 6;
+const ROW_4: usize = 4;
+const ROW_7: usize = 1;
+const ROW_8: usize = 0;
+
+const COL_B: usize = 1;
+const COL_C: usize = 2;
+const COL_D: usize = 3;
+const COL_E: usize = 4;
+const COL_F: usize = 5;
+const COL_G: usize = 6;
+
+const BISHOP_DIRS: [(isize, isize); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+const ROOK_DIRS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const QUEEN_DIRS: [(isize, isize); 8] =
+    [(-1, -1), (-1, 1), (1, -1), (1, 1), (-1, 0), (1, 0), (0, -1), (0, 1)];
+const KNIGHT_OFFSETS: [(isize, isize); 8] =
+    [(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)];
+
+// A move is represented the same way `Position::make_move` wants it: source square, destination
+// square, and an optional promotion piece type.
+pub type Move = ((usize, usize), (usize, usize), Option<PieceType>);
+
+// Generates every legal move available to the side to move.
+//
+// This first generates pseudo-legal moves (moves that obey how each piece type moves, but that
+// might leave the mover's own king in check) and then filters out the ones that don't hold up:
+// each candidate is played on a clone of `pos` and kept only if the mover's king is safe
+// afterward.
+pub fn legal_moves(pos: &Position) -> Vec<Move> {
+    let color = pos.side_to_move();
+    pseudo_legal_moves(pos, color)
+        .into_iter()
+        .filter(|&(src, dst, promotion)| {
+            let mut after = pos.clone();
+            after.make_move(src, dst, promotion);
+            !is_in_check(&after, color)
+        })
+        .collect()
+}
+
+// Whether `color`'s king is currently attacked.
+pub fn is_in_check(pos: &Position, color: Color) -> bool {
+    match find_king(pos, color) {
+        Some(square) => is_square_attacked(pos, square, opposite_color(color)),
+        None => false
+    }
+}
+
+// Formats a move in UCI long algebraic coordinate notation, e.g. "e2e4" or "e7e8q".
+pub fn move_to_uci(mv: Move) -> String {
+    let (src, dst, promotion) = mv;
+    let mut uci = square_to_algebraic(src) + &square_to_algebraic(dst);
+    if let Some(piece_type) = promotion {
+        uci.push(promotion_char(piece_type));
+    }
+    uci
+}
+
+fn square_to_algebraic(square: (usize, usize)) -> String {
+    let (row, col) = square;
+    let file = (b'a' + col as u8) as char;
+    let rank = (b'1' + (7 - row) as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+fn promotion_char(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Queen => 'q',
+        PieceType::Rook => 'r',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        _ => unreachable!("Can't promote to this piece type")
+    }
+}
+
+fn pseudo_legal_moves(pos: &Position, color: Color) -> Vec<Move> {
+    let mut moves = Vec::new();
+    for row in 0..8 {
+        for col in 0..8 {
+            if let Cell::Piece(piece_type, piece_color) = pos.cell_at(row, col) {
+                if piece_color != color {
+                    continue;
+                }
+                let src = (row, col);
+                match piece_type {
+                    PieceType::Pawn => add_pawn_moves(pos, src, color, &mut moves),
+                    PieceType::Knight => add_offset_moves(pos, src, color, &KNIGHT_OFFSETS, &mut moves),
+                    PieceType::Bishop => add_sliding_moves(pos, src, color, &BISHOP_DIRS, &mut moves),
+                    PieceType::Rook => add_sliding_moves(pos, src, color, &ROOK_DIRS, &mut moves),
+                    PieceType::Queen => add_sliding_moves(pos, src, color, &QUEEN_DIRS, &mut moves),
+                    PieceType::King => {
+                        add_offset_moves(pos, src, color, &QUEEN_DIRS, &mut moves);
+                        add_castling_moves(pos, color, &mut moves);
+                    }
+                }
+            }
+        }
+    }
+    moves
+}
+
+// Walks each ray in `dirs` from `src` until it falls off the board or hits a blocker. A friendly
+// blocker stops the ray before its square; an enemy blocker is included (as a capture) and then
+// stops the ray.
+fn add_sliding_moves(pos: &Position,
+                      src: (usize, usize),
+                      color: Color,
+                      dirs: &[(isize, isize)],
+                      moves: &mut Vec<Move>) {
+    for &dir in dirs {
+        let mut row = src.0 as isize + dir.0;
+        let mut col = src.1 as isize + dir.1;
+        while on_board(row, col) {
+            let dst = (row as usize, col as usize);
+            let dst_cell = pos.cell_at(dst.0, dst.1);
+            if can_piece_move_into_cell(dst_cell, color) {
+                moves.push((src, dst, None));
+            }
+            if dst_cell != Cell::Empty {
+                break;
+            }
+            row += dir.0;
+            col += dir.1;
+        }
+    }
+}
+
+// Like `add_sliding_moves`, but for pieces that move a single step (knights and kings).
+fn add_offset_moves(pos: &Position,
+                     src: (usize, usize),
+                     color: Color,
+                     offsets: &[(isize, isize)],
+                     moves: &mut Vec<Move>) {
+    for &offset in offsets {
+        let row = src.0 as isize + offset.0;
+        let col = src.1 as isize + offset.1;
+        if on_board(row, col) {
+            let dst = (row as usize, col as usize);
+            if can_piece_move_into_cell(pos.cell_at(dst.0, dst.1), color) {
+                moves.push((src, dst, None));
+            }
+        }
+    }
+}
+
+fn add_pawn_moves(pos: &Position, src: (usize, usize), color: Color, moves: &mut Vec<Move>) {
+    let (dir, start_row, promotion_row): (isize, usize, usize) = match color {
+        Color::White => (-1, ROW_2, ROW_8),
+        Color::Black => (1, ROW_7, ROW_1)
+    };
+    let one_row = src.0 as isize + dir;
+
+    // Single and double push
+    if on_board(one_row, src.1 as isize) {
+        let one = (one_row as usize, src.1);
+        if pos.cell_at(one.0, one.1) == Cell::Empty {
+            add_pawn_destination(src, one, promotion_row, moves);
+            if src.0 == start_row {
+                let two = ((src.0 as isize + 2 * dir) as usize, src.1);
+                if pos.cell_at(two.0, two.1) == Cell::Empty {
+                    moves.push((src, two, None));
+                }
+            }
+        }
+    }
+
+    // Captures, including en passant
+    for &dc in &[-1isize, 1] {
+        let cap_col = src.1 as isize + dc;
+        if !on_board(one_row, cap_col) {
+            continue;
+        }
+        let dst = (one_row as usize, cap_col as usize);
+        match pos.cell_at(dst.0, dst.1) {
+            Cell::Piece(_, piece_color) if piece_color == opposite_color(color) => {
+                add_pawn_destination(src, dst, promotion_row, moves);
+            },
+            Cell::Empty if pos.en_passant() == Some(dst) => {
+                moves.push((src, dst, None));
+            },
+            _ => ()
+        }
+    }
+}
+
+fn add_pawn_destination(src: (usize, usize),
+                         dst: (usize, usize),
+                         promotion_row: usize,
+                         moves: &mut Vec<Move>) {
+    if dst.0 == promotion_row {
+        for &piece_type in &[PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+            moves.push((src, dst, Some(piece_type)));
+        }
+    } else {
+        moves.push((src, dst, None));
+    }
+}
+
+// Castling is gated by the four castling-rights flags, by the squares between king and rook
+// being empty, and by the king neither starting, passing through, nor landing on an attacked
+// square.
+fn add_castling_moves(pos: &Position, color: Color, moves: &mut Vec<Move>) {
+    if is_in_check(pos, color) {
+        return;
+    }
+    let row = match color {
+        Color::White => ROW_1,
+        Color::Black => ROW_8
+    };
+    let (can_kingside, can_queenside) = match color {
+        Color::White => (pos.white_can_castle_kingside(), pos.white_can_castle_queenside()),
+        Color::Black => (pos.black_can_castle_kingside(), pos.black_can_castle_queenside())
+    };
+    let enemy = opposite_color(color);
+
+    if can_kingside
+        && pos.cell_at(row, COL_F) == Cell::Empty
+        && pos.cell_at(row, COL_G) == Cell::Empty
+        && !is_square_attacked(pos, (row, COL_F), enemy)
+        && !is_square_attacked(pos, (row, COL_G), enemy)
+    {
+        moves.push(((row, COL_E), (row, COL_G), None));
+    }
+    if can_queenside
+        && pos.cell_at(row, COL_D) == Cell::Empty
+        && pos.cell_at(row, COL_C) == Cell::Empty
+        && pos.cell_at(row, COL_B) == Cell::Empty
+        && !is_square_attacked(pos, (row, COL_D), enemy)
+        && !is_square_attacked(pos, (row, COL_C), enemy)
+    {
+        moves.push(((row, COL_E), (row, COL_C), None));
+    }
+}
+
+fn find_king(pos: &Position, color: Color) -> Option<(usize, usize)> {
+    for row in 0..8 {
+        for col in 0..8 {
+            if pos.cell_at(row, col) == Cell::Piece(PieceType::King, color) {
+                return Some((row, col));
+            }
+        }
+    }
+    None
+}
+
+// Whether `square` is attacked by a piece of `by_color`. This is independent of whose turn it is
+// and of whether `square` is occupied, so it also works for the squares a castling king passes
+// through.
+fn is_square_attacked(pos: &Position, square: (usize, usize), by_color: Color) -> bool {
+    KNIGHT_OFFSETS.iter().any(|&offset| offset_has_piece(pos, square, offset, PieceType::Knight, by_color))
+        || QUEEN_DIRS.iter().any(|&offset| offset_has_piece(pos, square, offset, PieceType::King, by_color))
+        || BISHOP_DIRS.iter().any(|&dir| ray_attacked_by(pos, square, dir, by_color, PieceType::Bishop))
+        || ROOK_DIRS.iter().any(|&dir| ray_attacked_by(pos, square, dir, by_color, PieceType::Rook))
+        || is_attacked_by_pawn(pos, square, by_color)
+}
+
+fn offset_has_piece(pos: &Position,
+                     square: (usize, usize),
+                     offset: (isize, isize),
+                     piece_type: PieceType,
+                     by_color: Color) -> bool {
+    let row = square.0 as isize + offset.0;
+    let col = square.1 as isize + offset.1;
+    on_board(row, col) && pos.cell_at(row as usize, col as usize) == Cell::Piece(piece_type, by_color)
+}
+
+// Looks for a `piece_type` or queen of `by_color` along `dir` from `square`, stopping at the
+// first occupied square.
+fn ray_attacked_by(pos: &Position,
+                    square: (usize, usize),
+                    dir: (isize, isize),
+                    by_color: Color,
+                    piece_type: PieceType) -> bool {
+    let mut row = square.0 as isize + dir.0;
+    let mut col = square.1 as isize + dir.1;
+    while on_board(row, col) {
+        if let Cell::Piece(cell_piece_type, cell_color) = pos.cell_at(row as usize, col as usize) {
+            return cell_color == by_color && (cell_piece_type == piece_type || cell_piece_type == PieceType::Queen);
+        }
+        row += dir.0;
+        col += dir.1;
+    }
+    false
+}
+
+fn is_attacked_by_pawn(pos: &Position, square: (usize, usize), by_color: Color) -> bool {
+    // A pawn attacks diagonally forward from its own perspective, which is diagonally backward
+    // from the perspective of the attacked square.
+    let dir: isize = match by_color {
+        Color::White => 1,
+        Color::Black => -1
+    };
+    let row = square.0 as isize + dir;
+    [-1isize, 1].iter().any(|&dc| {
+        let col = square.1 as isize + dc;
+        on_board(row, col) && pos.cell_at(row as usize, col as usize) == Cell::Piece(PieceType::Pawn, by_color)
+    })
+}
+
+fn on_board(row: isize, col: isize) -> bool {
+    row >= 0 && row < 8 && col >= 0 && col < 8
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use position::Position;
+
+    #[test]
+    fn startpos_has_twenty_moves() {
+        let pos = Position::new();
+        assert_eq!(legal_moves(&pos).len(), 20);
+    }
+
+    #[test]
+    fn pawn_on_seventh_rank_has_four_promotions() {
+        let pos = Position::from_fen_or_panic("k7/4P3/8/8/8/8/8/K7 w - - 0 1");
+        let promotions = legal_moves(&pos).into_iter()
+            .filter(|&(src, _, promotion)| src == (ROW_7, 4) && promotion.is_some())
+            .count();
+        assert_eq!(promotions, 4);
+    }
+
+    #[test]
+    fn king_cannot_castle_out_of_check() {
+        let pos = Position::from_fen_or_panic("4k3/8/8/8/8/8/4r3/4K2R w K - 0 1");
+        assert!(!legal_moves(&pos).contains(&((ROW_1, COL_E), (ROW_1, COL_G), None)));
+    }
+
+    #[test]
+    fn king_cannot_castle_through_attacked_square() {
+        let pos = Position::from_fen_or_panic("4k3/8/8/8/8/8/5r2/4K2R w K - 0 1");
+        assert!(!legal_moves(&pos).contains(&((ROW_1, COL_E), (ROW_1, COL_G), None)));
+    }
+
+    #[test]
+    fn king_can_castle_kingside() {
+        let pos = Position::from_fen_or_panic("4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        assert!(legal_moves(&pos).contains(&((ROW_1, COL_E), (ROW_1, COL_G), None)));
+    }
+
+    #[test]
+    fn formats_move_to_uci() {
+        assert_eq!(move_to_uci(((ROW_2, COL_E), (ROW_4, COL_E), None)), "e2e4");
+        assert_eq!(move_to_uci(((ROW_7, COL_E), (ROW_8, COL_E), Some(PieceType::Queen))), "e7e8q");
+    }
+
+    #[test]
+    fn bishop_gives_check_diagonally() {
+        let pos = Position::from_fen_or_panic("4k3/8/8/8/8/8/3b4/4K3 w - - 0 1");
+        assert!(is_in_check(&pos, Color::White));
+    }
+}
@@ -0,0 +1,116 @@
+use movegen::legal_moves;
+use movegen::Move;
+use position::Position;
+
+// Counts the leaf nodes reachable from `pos` by playing out every legal move, to `depth` plies.
+// This is the standard way to validate a move generator: the node counts for well-known positions
+// are documented and widely reproduced, so a mismatch against them means a move-generation bug.
+pub fn perft(pos: &mut Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut nodes = 0;
+    for (src, dst, promotion) in legal_moves(pos) {
+        let undo = pos.make_move(src, dst, promotion);
+        nodes += perft(pos, depth - 1);
+        pos.unmake_move(src, dst, promotion, undo);
+    }
+    nodes
+}
+
+// Like `perft`, but broken down by root move. This is the standard way to localize a
+// move-generation bug: compare against a known-good engine's divide output, and the first root
+// move with a mismatched count is where the bug is. There's no meaningful breakdown at depth 0
+// (no move has been played yet), so that's an empty list rather than an underflow.
+pub fn divide(pos: &mut Position, depth: u32) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    legal_moves(pos).into_iter().map(|mv| {
+        let (src, dst, promotion) = mv;
+        let undo = pos.make_move(src, dst, promotion);
+        let nodes = perft(pos, depth - 1);
+        pos.unmake_move(src, dst, promotion, undo);
+        (mv, nodes)
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIWIPETE_FEN: &str =
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    // A standard perft test position in which White's d7 pawn can capture-promote on c8 at the
+    // first ply, alongside Kiwipete (castling, and en passant from depth 2) and startpos (the
+    // general case). No en passant capture is possible within the two plies tested here.
+    const PROMOTION_FEN: &str =
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+
+    #[test]
+    fn startpos_perft_1() {
+        let mut pos = Position::new();
+        assert_eq!(perft(&mut pos, 1), 20);
+    }
+
+    #[test]
+    fn startpos_perft_2() {
+        let mut pos = Position::new();
+        assert_eq!(perft(&mut pos, 2), 400);
+    }
+
+    #[test]
+    fn startpos_perft_3() {
+        let mut pos = Position::new();
+        assert_eq!(perft(&mut pos, 3), 8902);
+    }
+
+    #[test]
+    fn kiwipete_perft_1() {
+        let mut pos = Position::from_fen_or_panic(KIWIPETE_FEN);
+        assert_eq!(perft(&mut pos, 1), 48);
+    }
+
+    #[test]
+    fn kiwipete_perft_2() {
+        let mut pos = Position::from_fen_or_panic(KIWIPETE_FEN);
+        assert_eq!(perft(&mut pos, 2), 2039);
+    }
+
+    #[test]
+    fn kiwipete_perft_3() {
+        // Depth 3 is the shallowest Kiwipete depth that plays a capture on a rook's home square
+        // (e.g. 1.Nxf7 and 2.Nxh8), but only as the last ply, so this count does not depend on
+        // castling rights being revoked after such a capture;
+        // `capturing_rook_on_home_square_revokes_castling_right` in position.rs checks that.
+        let mut pos = Position::from_fen_or_panic(KIWIPETE_FEN);
+        assert_eq!(perft(&mut pos, 3), 97862);
+    }
+
+    #[test]
+    fn promotion_perft_1() {
+        let mut pos = Position::from_fen_or_panic(PROMOTION_FEN);
+        assert_eq!(perft(&mut pos, 1), 44);
+    }
+
+    #[test]
+    fn promotion_perft_2() {
+        let mut pos = Position::from_fen_or_panic(PROMOTION_FEN);
+        assert_eq!(perft(&mut pos, 2), 1486);
+    }
+
+    #[test]
+    fn divide_at_depth_zero_is_empty() {
+        let mut pos = Position::new();
+        assert_eq!(divide(&mut pos, 0), Vec::new());
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut pos = Position::new();
+        let total: u64 = divide(&mut pos, 2).into_iter().map(|(_, nodes)| nodes).sum();
+        assert_eq!(total, perft(&mut pos, 2));
+    }
+}
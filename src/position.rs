@@ -4,6 +4,7 @@ use regex::Regex;
 use color::Color;
 use color::opposite_color;
 use piece::PieceType;
+use zobrist;
 
 
 const BOARD_NUM_CELLS: usize = 120;
@@ -35,7 +36,7 @@ pub enum Cell {
     Piece(PieceType, Color)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Position {
     cells: [[Cell; 8]; 8],
     side_to_move: Color,
@@ -44,19 +45,41 @@ pub struct Position {
     white_can_castle_queenside: bool,
     black_can_castle_kingside: bool,
     black_can_castle_queenside: bool,
-    en_passant: Option<(usize, usize)>
+    en_passant: Option<(usize, usize)>,
+    zobrist_key: u64
+}
+
+// Everything `unmake_move` needs to reverse a `make_move` call that the board itself doesn't
+// still carry after the fact, e.g. the captured piece or the previous castling rights.
+#[derive(Debug, PartialEq)]
+pub struct Undo {
+    captured: Cell,
+    captured_square: (usize, usize),
+    en_passant: Option<(usize, usize)>,
+    white_can_castle_kingside: bool,
+    white_can_castle_queenside: bool,
+    black_can_castle_kingside: bool,
+    black_can_castle_queenside: bool,
+    halfmove_clock: u32,
+    zobrist_key: u64
+}
+
+// Why a FEN string was rejected by `from_fen`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FenError {
+    // The string didn't even match the overall FEN shape.
+    Malformed,
+    // The board part matched the regex, but some rank's square count didn't add up to 8.
+    InvalidRank { rank: usize, square_count: usize }
 }
 
 impl Position {
     pub fn new() -> Position {
-        Position::from_fen(STARTPOS_FEN)
+        Position::from_fen_or_panic(STARTPOS_FEN)
     }
 
-    // TODO: Panics if we're given invalid FEN.
-    // That would mean either we've got a bug or the GUI is not sane anyhow,
-    // so probably no big loss.
     // NOTE: move number will be ignored because it's not interesting.
-    pub fn from_fen(fen: &str) -> Position {
+    pub fn from_fen(fen: &str) -> Result<Position, FenError> {
         lazy_static! {
             static ref RE: Regex = Regex::new("^([PpNnBbRrQqKk1-8]+)/\
                                                ([PpNnBbRrQqKk1-8]+)/\
@@ -72,11 +95,10 @@ impl Position {
                                                (?P<halfmove_clock>[0-9]+) \
                                                (?P<move_number>[0-9]+)$").unwrap();
         }
-        let captures = RE.captures(fen);
-        if captures.is_none() {
-            panic!("Invalid FEN (failed to match regex)");
-        }
-        let captures = captures.unwrap();
+        let captures = match RE.captures(fen) {
+            Some(captures) => captures,
+            None => return Err(FenError::Malformed)
+        };
         let side_to_move = match captures.name("side_to_move").unwrap().as_str() {
             "w" => Color::White,
             "b" => Color::Black,
@@ -89,16 +111,96 @@ impl Position {
             "-" => None,
             _ => Some(parse_algebraic_coords(en_passant))
         };
-        Position {
-            cells: read_board_data(&captures),
+        let cells = read_board_data(&captures)?;
+        let white_can_castle_kingside = castling.contains('K');
+        let white_can_castle_queenside = castling.contains('Q');
+        let black_can_castle_kingside = castling.contains('k');
+        let black_can_castle_queenside = castling.contains('q');
+        let zobrist_key = initial_zobrist_key(&cells,
+                                               side_to_move,
+                                               white_can_castle_kingside,
+                                               white_can_castle_queenside,
+                                               black_can_castle_kingside,
+                                               black_can_castle_queenside,
+                                               en_passant);
+        Ok(Position {
+            cells: cells,
             side_to_move: side_to_move,
             halfmove_clock: halfmove_clock,
-            white_can_castle_kingside: castling.contains('K'),
-            white_can_castle_queenside: castling.contains('Q'),
-            black_can_castle_kingside: castling.contains('k'),
-            black_can_castle_queenside: castling.contains('q'),
-            en_passant: en_passant
+            white_can_castle_kingside: white_can_castle_kingside,
+            white_can_castle_queenside: white_can_castle_queenside,
+            black_can_castle_kingside: black_can_castle_kingside,
+            black_can_castle_queenside: black_can_castle_queenside,
+            en_passant: en_passant,
+            zobrist_key: zobrist_key
+        })
+    }
+
+    // A thin wrapper around `from_fen` for callers (mainly tests) that already know their FEN is
+    // valid and would rather panic loudly on a typo than thread a `Result` through.
+    pub fn from_fen_or_panic(fen: &str) -> Position {
+        Position::from_fen(fen).expect("Invalid FEN")
+    }
+
+    // Inverse of `from_fen`: serializes the board, side to move, castling rights, en passant
+    // square, and half-move clock back into a FEN string. The full-move number isn't tracked by
+    // `Position`, so it's always written as 1.
+    pub fn to_fen(&self) -> String {
+        let mut ranks = Vec::with_capacity(8);
+        for row in 0..8 {
+            let mut rank = String::new();
+            let mut empty_run = 0;
+            for col in 0..8 {
+                match self.cells[row][col] {
+                    Cell::Empty => empty_run += 1,
+                    Cell::Piece(piece_type, color) => {
+                        if empty_run > 0 {
+                            rank.push_str(&empty_run.to_string());
+                            empty_run = 0;
+                        }
+                        rank.push(piece_to_fen_char(piece_type, color));
+                    }
+                }
+            }
+            if empty_run > 0 {
+                rank.push_str(&empty_run.to_string());
+            }
+            ranks.push(rank);
+        }
+
+        let side_to_move = match self.side_to_move {
+            Color::White => "w",
+            Color::Black => "b"
+        };
+
+        let mut castling = String::new();
+        if self.white_can_castle_kingside {
+            castling.push('K');
         }
+        if self.white_can_castle_queenside {
+            castling.push('Q');
+        }
+        if self.black_can_castle_kingside {
+            castling.push('k');
+        }
+        if self.black_can_castle_queenside {
+            castling.push('q');
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some(square) => square_to_algebraic(square),
+            None => "-".to_string()
+        };
+
+        format!("{} {} {} {} {} 1",
+                ranks.join("/"),
+                side_to_move,
+                castling,
+                en_passant,
+                self.halfmove_clock)
     }
 
     // NOTE: Does not check move's legality! It just replaces the destination square with the
@@ -112,12 +214,19 @@ impl Position {
     pub fn make_move(&mut self,
                      src: (usize, usize),
                      dst: (usize, usize),
-                     promotion_type: Option<PieceType>) {
+                     promotion_type: Option<PieceType>) -> Undo {
         let (src_row, src_col) = src;
         let (dst_row, dst_col) = dst;
         let src_cell = self.cells[src_row][src_col];
+        let dst_cell = self.cells[dst_row][dst_col];
+        let zobrist_key_before = self.zobrist_key;
+        let old_halfmove_clock = self.halfmove_clock;
+        let mut captured = dst_cell;
+        let mut captured_square = dst;
+        self.xor_zobrist_piece(src_cell, src);
+        self.xor_zobrist_piece(dst_cell, dst);
         self.cells[src_row][src_col] = Cell::Empty;
-        self.halfmove_clock = if self.cells[dst_row][dst_col] == Cell::Empty {
+        self.halfmove_clock = if dst_cell == Cell::Empty {
             // Not a capture; advance half-move clock
             self.halfmove_clock + 1
         } else {
@@ -125,6 +234,22 @@ impl Position {
             0
         };
         self.cells[dst_row][dst_col] = src_cell;
+        let old_white_kingside = self.white_can_castle_kingside;
+        let old_white_queenside = self.white_can_castle_queenside;
+        let old_black_kingside = self.black_can_castle_kingside;
+        let old_black_queenside = self.black_can_castle_queenside;
+        let old_en_passant = self.en_passant;
+        // A rook captured on its home square loses its side the matching castling right, even
+        // though the rook itself never moved.
+        if let Cell::Piece(PieceType::Rook, captured_color) = dst_cell {
+            match (captured_color, dst_row, dst_col) {
+                (Color::White, ROW_1, COL_A) => self.white_can_castle_queenside = false,
+                (Color::White, ROW_1, COL_H) => self.white_can_castle_kingside = false,
+                (Color::Black, ROW_8, COL_A) => self.black_can_castle_queenside = false,
+                (Color::Black, ROW_8, COL_H) => self.black_can_castle_kingside = false,
+                _ => ()
+            }
+        }
         if let Cell::Piece(piece_type, color) = src_cell {
             match piece_type {
                 PieceType::Pawn => {
@@ -135,7 +260,10 @@ impl Position {
                             // Capturing en passant; remove the captured pawn
                             // Notice the captured pawn is on the *source* row and on the
                             // *destination* column
-                            self.cells[src_row][dst_col] = Cell::Empty;
+                            captured_square = (src_row, dst_col);
+                            captured = self.cells[captured_square.0][captured_square.1];
+                            self.xor_zobrist_piece(captured, captured_square);
+                            self.cells[captured_square.0][captured_square.1] = Cell::Empty;
                         }
                     }
                     if dst_row == ROW_1 || dst_row == ROW_8 {
@@ -175,14 +303,18 @@ impl Position {
                         if dst_col == COL_G {
                             // Kingside castling; move the rook too
                             let rook = self.cells[src_row][COL_H];
+                            self.xor_zobrist_piece(rook, (src_row, COL_H));
                             self.cells[src_row][COL_H] = Cell::Empty;
                             self.cells[src_row][COL_F] = rook;
+                            self.xor_zobrist_piece(rook, (src_row, COL_F));
                         }
                         else if src_col == COL_E && dst_col == COL_C {
                             // Queenside castling; move the rook too
                             let rook = self.cells[src_row][COL_A];
+                            self.xor_zobrist_piece(rook, (src_row, COL_A));
                             self.cells[src_row][COL_A] = Cell::Empty;
                             self.cells[src_row][COL_D] = rook;
+                            self.xor_zobrist_piece(rook, (src_row, COL_D));
                         }
                     }
                 }
@@ -206,11 +338,128 @@ impl Position {
         } else {
             panic!("Move from empty square");
         }
+        // The moving piece may have changed (promotion), so XOR it back in from its final resting
+        // place rather than tracking the promoted type above.
+        self.xor_zobrist_piece(self.cells[dst_row][dst_col], dst);
+        if old_en_passant != self.en_passant {
+            if let Some((_, col)) = old_en_passant {
+                self.zobrist_key ^= zobrist::en_passant_file_key(col);
+            }
+            if let Some((_, col)) = self.en_passant {
+                self.zobrist_key ^= zobrist::en_passant_file_key(col);
+            }
+        }
+        if old_white_kingside && !self.white_can_castle_kingside {
+            self.zobrist_key ^= zobrist::white_kingside_castle_key();
+        }
+        if old_white_queenside && !self.white_can_castle_queenside {
+            self.zobrist_key ^= zobrist::white_queenside_castle_key();
+        }
+        if old_black_kingside && !self.black_can_castle_kingside {
+            self.zobrist_key ^= zobrist::black_kingside_castle_key();
+        }
+        if old_black_queenside && !self.black_can_castle_queenside {
+            self.zobrist_key ^= zobrist::black_queenside_castle_key();
+        }
         self.side_to_move = opposite_color(self.side_to_move);
+        self.zobrist_key ^= zobrist::side_to_move_key();
+
+        Undo {
+            captured: captured,
+            captured_square: captured_square,
+            en_passant: old_en_passant,
+            white_can_castle_kingside: old_white_kingside,
+            white_can_castle_queenside: old_white_queenside,
+            black_can_castle_kingside: old_black_kingside,
+            black_can_castle_queenside: old_black_queenside,
+            halfmove_clock: old_halfmove_clock,
+            zobrist_key: zobrist_key_before
+        }
+    }
+
+    // Reverses a `make_move` call. `src`, `dst`, and `promotion_type` must be exactly the
+    // arguments that were passed to the `make_move` call that produced `undo`, and `unmake_move`
+    // must be called at most once per `Undo` (it consumes the record). This lets search walk the
+    // tree with a single `Position` instead of cloning at every node.
+    pub fn unmake_move(&mut self,
+                        src: (usize, usize),
+                        dst: (usize, usize),
+                        promotion_type: Option<PieceType>,
+                        undo: Undo) {
+        let (src_row, src_col) = src;
+        let (dst_row, dst_col) = dst;
+
+        let moved_cell = match self.cells[dst_row][dst_col] {
+            Cell::Piece(_, color) if promotion_type.is_some() => Cell::Piece(PieceType::Pawn, color),
+            moved_cell => moved_cell
+        };
+
+        self.cells[dst_row][dst_col] = Cell::Empty;
+        if let Cell::Piece(PieceType::King, _) = moved_cell {
+            // Undo the rook's half of a castling move, if any
+            if src_col == COL_E && dst_col == COL_G {
+                let rook = self.cells[src_row][COL_F];
+                self.cells[src_row][COL_F] = Cell::Empty;
+                self.cells[src_row][COL_H] = rook;
+            } else if src_col == COL_E && dst_col == COL_C {
+                let rook = self.cells[src_row][COL_D];
+                self.cells[src_row][COL_D] = Cell::Empty;
+                self.cells[src_row][COL_A] = rook;
+            }
+        }
+        self.cells[src_row][src_col] = moved_cell;
+        self.cells[undo.captured_square.0][undo.captured_square.1] = undo.captured;
+
+        self.en_passant = undo.en_passant;
+        self.white_can_castle_kingside = undo.white_can_castle_kingside;
+        self.white_can_castle_queenside = undo.white_can_castle_queenside;
+        self.black_can_castle_kingside = undo.black_can_castle_kingside;
+        self.black_can_castle_queenside = undo.black_can_castle_queenside;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.side_to_move = opposite_color(self.side_to_move);
+        self.zobrist_key = undo.zobrist_key;
+    }
+
+    pub fn zobrist(&self) -> u64 {
+        self.zobrist_key
+    }
+
+    fn xor_zobrist_piece(&mut self, cell: Cell, square: (usize, usize)) {
+        if let Cell::Piece(piece_type, color) = cell {
+            self.zobrist_key ^= zobrist::piece_key(piece_type, color, square);
+        }
+    }
+
+    pub fn cell_at(&self, row: usize, col: usize) -> Cell {
+        self.cells[row][col]
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    pub fn en_passant(&self) -> Option<(usize, usize)> {
+        self.en_passant
+    }
+
+    pub fn white_can_castle_kingside(&self) -> bool {
+        self.white_can_castle_kingside
+    }
+
+    pub fn white_can_castle_queenside(&self) -> bool {
+        self.white_can_castle_queenside
+    }
+
+    pub fn black_can_castle_kingside(&self) -> bool {
+        self.black_can_castle_kingside
+    }
+
+    pub fn black_can_castle_queenside(&self) -> bool {
+        self.black_can_castle_queenside
     }
 }
 
-fn read_board_data(captures: &Captures) -> [[Cell; 8]; 8] {
+fn read_board_data(captures: &Captures) -> Result<[[Cell; 8]; 8], FenError> {
     let mut cells: [[Cell; 8]; 8] = [[Cell::Empty; 8]; 8];
     for row in 0..8 {
         let row_str = &captures[row+1];
@@ -219,6 +468,9 @@ fn read_board_data(captures: &Captures) -> [[Cell; 8]; 8] {
             if let Some(digit) = ch.to_digit(10) {
                 col += digit as usize;
             } else {
+                if col >= 8 {
+                    return Err(FenError::InvalidRank { rank: row, square_count: col + 1 });
+                }
                 cells[row][col] = match ch {
                     'P' => Cell::Piece(PieceType::Pawn, Color::White),
                     'p' => Cell::Piece(PieceType::Pawn, Color::Black),
@@ -237,8 +489,69 @@ fn read_board_data(captures: &Captures) -> [[Cell; 8]; 8] {
                 col += 1;
             }
         }
+        if col != 8 {
+            return Err(FenError::InvalidRank { rank: row, square_count: col });
+        }
+    }
+    Ok(cells)
+}
+
+fn piece_to_fen_char(piece_type: PieceType, color: Color) -> char {
+    let ch = match piece_type {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k'
+    };
+    match color {
+        Color::White => ch.to_ascii_uppercase(),
+        Color::Black => ch
     }
-    cells
+}
+
+fn square_to_algebraic(square: (usize, usize)) -> String {
+    let (row, col) = square;
+    let file = (b'a' + col as u8) as char;
+    let rank = (b'1' + (7 - row) as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+fn initial_zobrist_key(cells: &[[Cell; 8]; 8],
+                        side_to_move: Color,
+                        white_can_castle_kingside: bool,
+                        white_can_castle_queenside: bool,
+                        black_can_castle_kingside: bool,
+                        black_can_castle_queenside: bool,
+                        en_passant: Option<(usize, usize)>) -> u64 {
+    let mut key = 0u64;
+    for row in 0..8 {
+        for col in 0..8 {
+            if let Cell::Piece(piece_type, color) = cells[row][col] {
+                key ^= zobrist::piece_key(piece_type, color, (row, col));
+            }
+        }
+    }
+    if side_to_move == Color::Black {
+        key ^= zobrist::side_to_move_key();
+    }
+    if white_can_castle_kingside {
+        key ^= zobrist::white_kingside_castle_key();
+    }
+    if white_can_castle_queenside {
+        key ^= zobrist::white_queenside_castle_key();
+    }
+    if black_can_castle_kingside {
+        key ^= zobrist::black_kingside_castle_key();
+    }
+    if black_can_castle_queenside {
+        key ^= zobrist::black_queenside_castle_key();
+    }
+    if let Some((_, col)) = en_passant {
+        key ^= zobrist::en_passant_file_key(col);
+    }
+    key
 }
 
 // This should probably be moved into a move generator module
@@ -251,7 +564,7 @@ pub fn can_piece_move_into_cell(cell: Cell, my_color: Color) -> bool {
 }
 
 // NOTE: Does not check the validity of the string!
-fn parse_algebraic_coords(coords: &str) -> (usize, usize) {
+pub fn parse_algebraic_coords(coords: &str) -> (usize, usize) {
     let mut iter = coords.chars();
     let col = iter.next().unwrap() as usize - 'a' as usize;
     let row = 7 - (iter.next().unwrap() as usize - '1' as usize);
@@ -265,7 +578,7 @@ mod tests {
 
     #[test]
     fn new_equals_startpos() {
-        assert_eq!(Position::new(), Position::from_fen(STARTPOS_FEN));
+        assert_eq!(Position::new(), Position::from_fen_or_panic(STARTPOS_FEN));
     }
 
     #[test]
@@ -273,7 +586,7 @@ mod tests {
         let mut pos = Position::new();
         pos.make_move((ROW_2, COL_E), (ROW_4, COL_E), None);
         assert_eq!(pos,
-                   Position::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"));
+                   Position::from_fen_or_panic("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"));
     }
 
     #[test]
@@ -286,74 +599,189 @@ mod tests {
         pos.make_move((ROW_1, COL_F), (ROW_4, COL_C), None);    // 3.Bc4
         pos.make_move((ROW_8, COL_F), (ROW_5, COL_C), None);    // 3.Bc5
         assert_eq!(pos,
-                   Position::from_fen("r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"));
+                   Position::from_fen_or_panic("r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"));
     }
 
     #[test]
     fn white_capturing_en_passant() {
-        let mut pos = Position::from_fen("k7/p7/8/1P6/8/8/8/K7 b - - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/p7/8/1P6/8/8/8/K7 b - - 0 1");
         pos.make_move((ROW_7, COL_A), (ROW_5, COL_A), None);    // 1...a5
         pos.make_move((ROW_5, COL_B), (ROW_6, COL_A), None);    // 2.bxa4 e.p.
         assert_eq!(pos,
-                   Position::from_fen("k7/8/P7/8/8/8/8/K7 b - - 0 2"));
+                   Position::from_fen_or_panic("k7/8/P7/8/8/8/8/K7 b - - 0 2"));
     }
 
     #[test]
     fn black_capturing_en_passant() {
-        let mut pos = Position::from_fen("k7/8/8/8/1p6/8/P7/K7 w - - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/8/8/8/1p6/8/P7/K7 w - - 0 1");
         pos.make_move((ROW_2, COL_A), (ROW_4, COL_A), None);    // 1.a4
         pos.make_move((ROW_4, COL_B), (ROW_3, COL_A), None);    // 1...bxa3 e.p.
         assert_eq!(pos,
-                   Position::from_fen("k7/8/8/8/8/p7/8/K7 w - - 0 2"));
+                   Position::from_fen_or_panic("k7/8/8/8/8/p7/8/K7 w - - 0 2"));
     }
 
     #[test]
     fn not_en_passant() {
         // Looks like en passant, but isn't, so don't remove the black pawn on row 6
-        let mut pos = Position::from_fen("k7/4p3/8/3Pp3/8/8/8/K7 b - - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/4p3/8/3Pp3/8/8/8/K7 b - - 0 1");
         pos.make_move((ROW_7, COL_E), (ROW_6, COL_E), None);    // 1...e6
         pos.make_move((ROW_5, COL_D), (ROW_6, COL_E), None);    // 2.dxe6
         assert_eq!(pos,
-                   Position::from_fen("k7/8/4P3/4p3/8/8/8/K7 b - - 0 2"));
+                   Position::from_fen_or_panic("k7/8/4P3/4p3/8/8/8/K7 b - - 0 2"));
     }
 
     #[test]
     fn white_castling_kingside() {
-        let mut pos = Position::from_fen("k7/8/8/8/8/8/8/4K2R w K - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/8/8/8/8/8/8/4K2R w K - 0 1");
         pos.make_move((ROW_1, COL_E), (ROW_1, COL_G), None);    // 1.O-O
         assert_eq!(pos,
-                   Position::from_fen("k7/8/8/8/8/8/8/5RK1 b - - 1 1"));
+                   Position::from_fen_or_panic("k7/8/8/8/8/8/8/5RK1 b - - 1 1"));
     }
 
     #[test]
     fn black_castling_kingside() {
-        let mut pos = Position::from_fen("4k2r/8/8/8/8/8/8/K7 b k - 0 1");
+        let mut pos = Position::from_fen_or_panic("4k2r/8/8/8/8/8/8/K7 b k - 0 1");
         pos.make_move((ROW_8, COL_E), (ROW_8, COL_G), None);    // 1...O-O
         assert_eq!(pos,
-                   Position::from_fen("5rk1/8/8/8/8/8/8/K7 w - - 1 2"));
+                   Position::from_fen_or_panic("5rk1/8/8/8/8/8/8/K7 w - - 1 2"));
     }
 
     #[test]
     fn white_castling_queenside() {
-        let mut pos = Position::from_fen("k7/8/8/8/8/8/8/R3K3 w K - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/8/8/8/8/8/8/R3K3 w K - 0 1");
         pos.make_move((ROW_1, COL_E), (ROW_1, COL_C), None);    // 1.O-O-O
         assert_eq!(pos,
-                   Position::from_fen("k7/8/8/8/8/8/8/2KR4 b - - 1 1"));
+                   Position::from_fen_or_panic("k7/8/8/8/8/8/8/2KR4 b - - 1 1"));
     }
 
     #[test]
     fn black_castling_queenside() {
-        let mut pos = Position::from_fen("r3k3/8/8/8/8/8/8/K7 b k - 0 1");
+        let mut pos = Position::from_fen_or_panic("r3k3/8/8/8/8/8/8/K7 b k - 0 1");
         pos.make_move((ROW_8, COL_E), (ROW_8, COL_C), None);    // 1...O-O-O
         assert_eq!(pos,
-                   Position::from_fen("2kr4/8/8/8/8/8/8/K7 w - - 1 2"));
+                   Position::from_fen_or_panic("2kr4/8/8/8/8/8/8/K7 w - - 1 2"));
+    }
+
+    #[test]
+    fn zobrist_key_is_move_order_independent() {
+        let mut via_knights_first = Position::new();
+        via_knights_first.make_move((ROW_1, COL_G), (ROW_3, COL_F), None);    // 1.Nf3
+        via_knights_first.make_move((ROW_8, COL_G), (ROW_6, COL_F), None);    // 1...Nf6
+        via_knights_first.make_move((ROW_1, COL_B), (ROW_3, COL_C), None);    // 2.Nc3
+        via_knights_first.make_move((ROW_8, COL_B), (ROW_6, COL_C), None);    // 2...Nc6
+
+        let mut via_other_order = Position::new();
+        via_other_order.make_move((ROW_1, COL_B), (ROW_3, COL_C), None);     // 1.Nc3
+        via_other_order.make_move((ROW_8, COL_B), (ROW_6, COL_C), None);     // 1...Nc6
+        via_other_order.make_move((ROW_1, COL_G), (ROW_3, COL_F), None);     // 2.Nf3
+        via_other_order.make_move((ROW_8, COL_G), (ROW_6, COL_F), None);     // 2...Nf6
+
+        assert_eq!(via_knights_first.zobrist(), via_other_order.zobrist());
+        assert_eq!(via_knights_first, via_other_order);
     }
 
     #[test]
     fn promote_pawn_to_knight() {
-        let mut pos = Position::from_fen("k7/4P3/8/8/8/8/8/K7 w - - 0 1");
+        let mut pos = Position::from_fen_or_panic("k7/4P3/8/8/8/8/8/K7 w - - 0 1");
         pos.make_move((ROW_7, COL_E), (ROW_8, COL_E), Some(PieceType::Knight)); // 1.e8=N
         assert_eq!(pos,
-                   Position::from_fen("k3N3/8/8/8/8/8/8/K7 b - - 0 1"));
+                   Position::from_fen_or_panic("k3N3/8/8/8/8/8/8/K7 b - - 0 1"));
+    }
+
+    #[test]
+    fn unmake_reverses_capture() {
+        let original = Position::from_fen_or_panic("k7/8/8/8/3p4/4P3/8/K7 w - - 0 1");
+        let mut pos = original.clone();
+        let undo = pos.make_move((ROW_3, COL_E), (ROW_4, COL_D), None);    // exd4
+        pos.unmake_move((ROW_3, COL_E), (ROW_4, COL_D), None, undo);
+        assert_eq!(pos, original);
+    }
+
+    #[test]
+    fn unmake_reverses_en_passant_capture() {
+        let original = Position::from_fen_or_panic("k7/8/8/1Pp5/8/8/8/K7 w - c6 0 1");
+        let mut pos = original.clone();
+        let undo = pos.make_move((ROW_5, COL_B), (ROW_6, COL_C), None);    // bxc6 e.p.
+        pos.unmake_move((ROW_5, COL_B), (ROW_6, COL_C), None, undo);
+        assert_eq!(pos, original);
+    }
+
+    #[test]
+    fn unmake_reverses_castling() {
+        let original = Position::from_fen_or_panic("k7/8/8/8/8/8/8/4K2R w K - 0 1");
+        let mut pos = original.clone();
+        let undo = pos.make_move((ROW_1, COL_E), (ROW_1, COL_G), None);    // O-O
+        pos.unmake_move((ROW_1, COL_E), (ROW_1, COL_G), None, undo);
+        assert_eq!(pos, original);
+    }
+
+    #[test]
+    fn unmake_reverses_promotion() {
+        let original = Position::from_fen_or_panic("k7/4P3/8/8/8/8/8/K7 w - - 0 1");
+        let mut pos = original.clone();
+        let undo = pos.make_move((ROW_7, COL_E), (ROW_8, COL_E), Some(PieceType::Queen)); // e8=Q
+        pos.unmake_move((ROW_7, COL_E), (ROW_8, COL_E), Some(PieceType::Queen), undo);
+        assert_eq!(pos, original);
+    }
+
+    #[test]
+    fn to_fen_round_trips_startpos() {
+        assert_eq!(Position::from_fen_or_panic(STARTPOS_FEN).to_fen(), STARTPOS_FEN);
+    }
+
+    #[test]
+    fn to_fen_round_trips_kiwipete() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(Position::from_fen_or_panic(fen).to_fen(), fen);
+    }
+
+    #[test]
+    fn to_fen_round_trips_en_passant_square() {
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(Position::from_fen_or_panic(fen).to_fen(), fen);
+    }
+
+    #[test]
+    fn to_fen_round_trips_no_castling_rights() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1";
+        assert_eq!(Position::from_fen_or_panic(fen).to_fen(), fen);
+    }
+
+    #[test]
+    fn from_fen_rejects_malformed_input() {
+        assert_eq!(Position::from_fen("not a fen"), Err(FenError::Malformed));
+    }
+
+    #[test]
+    fn from_fen_rejects_rank_with_too_few_squares() {
+        // First rank only accounts for 7 squares, not 8
+        let fen = "6k/8/8/8/8/8/8/7K w - - 0 1";
+        assert_eq!(Position::from_fen(fen), Err(FenError::InvalidRank { rank: 0, square_count: 7 }));
+    }
+
+    #[test]
+    fn from_fen_rejects_rank_with_too_many_squares() {
+        let fen = "8P/8/8/8/8/8/8/7K w - - 0 1";
+        assert_eq!(Position::from_fen(fen),
+                   Err(FenError::InvalidRank { rank: 0, square_count: 9 }));
+    }
+
+    #[test]
+    fn capturing_rook_on_home_square_revokes_castling_right() {
+        // Black's rook takes the white rook on h1; white must no longer be able to castle
+        // kingside even though its king and rook never moved.
+        let mut pos = Position::from_fen_or_panic("4k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+        pos.make_move((ROW_8, COL_H), (ROW_1, COL_H), None);    // 1...Rxh1
+        assert!(!pos.white_can_castle_kingside());
+        assert_eq!(pos, Position::from_fen_or_panic("4k3/8/8/8/8/8/8/4K2r w - - 0 2"));
+    }
+
+    #[test]
+    fn unmake_reverses_rook_capture_castling_right() {
+        let original = Position::from_fen_or_panic("4k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+        let mut pos = original.clone();
+        let undo = pos.make_move((ROW_8, COL_H), (ROW_1, COL_H), None);    // 1...Rxh1
+        pos.unmake_move((ROW_8, COL_H), (ROW_1, COL_H), None, undo);
+        assert_eq!(pos, original);
     }
 }
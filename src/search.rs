@@ -0,0 +1,135 @@
+use movegen::is_in_check;
+use movegen::legal_moves;
+use movegen::Move;
+use piece::PieceType;
+use position::Cell;
+use position::Position;
+
+const MATE_SCORE: i32 = 1_000_000;
+
+// How often (in visited nodes) the search checks whether it should stop. Checking on every node
+// would be needlessly expensive; checking too rarely makes the engine slow to respond to "stop"
+// or to running out of its time budget.
+const STOP_CHECK_INTERVAL: u64 = 2048;
+
+// Runs iterative deepening negamax up to `max_depth` plies, calling `should_stop` periodically so
+// the caller can interrupt the search (e.g. on a UCI "stop" or when a time budget runs out).
+// Returns the best move found at the deepest depth that finished before being interrupted (or, if
+// even depth 1 was interrupted, the best move of that partial depth), or `None` if `pos` has no
+// legal moves.
+pub fn search<F>(pos: &mut Position, max_depth: u32, mut should_stop: F) -> Option<Move>
+    where F: FnMut() -> bool
+{
+    let mut nodes = 0u64;
+    let mut best_move = None;
+    for depth in 1..=max_depth {
+        let mut depth_best_move = None;
+        let mut depth_best_score = -MATE_SCORE - 1;
+        let mut interrupted = false;
+        for (src, dst, promotion) in legal_moves(pos) {
+            let undo = pos.make_move(src, dst, promotion);
+            let score = -negamax(pos, depth - 1, &mut nodes, &mut should_stop, &mut interrupted);
+            pos.unmake_move(src, dst, promotion, undo);
+            if depth_best_move.is_none() || score > depth_best_score {
+                depth_best_score = score;
+                depth_best_move = Some((src, dst, promotion));
+            }
+            if interrupted {
+                break;
+            }
+        }
+        if interrupted {
+            // This depth didn't finish, so its move may be worse than a fully-searched shallower
+            // depth would have found; only keep it if we don't have a move yet at all.
+            if best_move.is_none() {
+                best_move = depth_best_move;
+            }
+            break;
+        }
+        println!("info depth {} score cp {} nodes {}", depth, depth_best_score, nodes);
+        best_move = depth_best_move;
+        if depth_best_move.is_none() {
+            break;    // No legal moves; no point deepening further
+        }
+    }
+    best_move
+}
+
+fn negamax<F>(pos: &mut Position,
+              depth: u32,
+              nodes: &mut u64,
+              should_stop: &mut F,
+              interrupted: &mut bool) -> i32
+    where F: FnMut() -> bool
+{
+    *nodes += 1;
+    if *nodes % STOP_CHECK_INTERVAL == 0 && should_stop() {
+        *interrupted = true;
+    }
+    if *interrupted || depth == 0 {
+        return evaluate(pos);
+    }
+    let moves = legal_moves(pos);
+    if moves.is_empty() {
+        return if is_in_check(pos, pos.side_to_move()) { -MATE_SCORE } else { 0 };
+    }
+    let mut best = -MATE_SCORE - 1;
+    for (src, dst, promotion) in moves {
+        let undo = pos.make_move(src, dst, promotion);
+        let score = -negamax(pos, depth - 1, nodes, should_stop, interrupted);
+        pos.unmake_move(src, dst, promotion, undo);
+        if score > best {
+            best = score;
+        }
+        if *interrupted {
+            break;
+        }
+    }
+    best
+}
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0
+    }
+}
+
+// A minimal material-only evaluation from the perspective of the side to move.
+fn evaluate(pos: &Position) -> i32 {
+    let mut score = 0;
+    for row in 0..8 {
+        for col in 0..8 {
+            if let Cell::Piece(piece_type, color) = pos.cell_at(row, col) {
+                let value = piece_value(piece_type);
+                score += if color == pos.side_to_move() { value } else { -value };
+            }
+        }
+    }
+    score
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use position::Position;
+
+    #[test]
+    fn finds_free_queen_capture() {
+        let mut pos = Position::from_fen_or_panic("4k3/8/8/1q6/8/8/8/4KQ2 w - - 0 1");
+        let best_move = search(&mut pos, 2, || false);
+        assert_eq!(best_move, Some(((7, 5), (3, 1), None)));
+    }
+
+    #[test]
+    fn no_legal_moves_returns_none() {
+        // Classic back-rank checkmate
+        let mut pos = Position::from_fen_or_panic("1k6/8/8/8/8/8/5PPP/r5K1 w - - 0 1");
+        assert_eq!(search(&mut pos, 1, || false), None);
+    }
+}
@@ -0,0 +1,105 @@
+use color::Color;
+use piece::PieceType;
+
+// The Zobrist key tables: one random key per (piece type, color, square), one for the side to
+// move, one per castling right, and one per en-passant file. `Position` XORs these in and out
+// incrementally as moves are made so it always has a cheap, collision-resistant hash suitable for
+// a transposition table key.
+struct ZobristKeys {
+    pieces: [[[u64; 64]; 2]; 6],
+    side_to_move: u64,
+    white_kingside_castle: u64,
+    white_queenside_castle: u64,
+    black_kingside_castle: u64,
+    black_queenside_castle: u64,
+    en_passant_file: [u64; 8]
+}
+
+impl ZobristKeys {
+    fn new() -> ZobristKeys {
+        let mut seed = 0x9E3779B97F4A7C15u64;
+        let mut next = || splitmix64(&mut seed);
+        let mut pieces = [[[0u64; 64]; 2]; 6];
+        for piece in pieces.iter_mut() {
+            for color in piece.iter_mut() {
+                for square in color.iter_mut() {
+                    *square = next();
+                }
+            }
+        }
+        let mut en_passant_file = [0u64; 8];
+        for file in en_passant_file.iter_mut() {
+            *file = next();
+        }
+        ZobristKeys {
+            pieces: pieces,
+            side_to_move: next(),
+            white_kingside_castle: next(),
+            white_queenside_castle: next(),
+            black_kingside_castle: next(),
+            black_queenside_castle: next(),
+            en_passant_file: en_passant_file
+        }
+    }
+}
+
+lazy_static! {
+    static ref KEYS: ZobristKeys = ZobristKeys::new();
+}
+
+pub fn piece_key(piece_type: PieceType, color: Color, square: (usize, usize)) -> u64 {
+    let (row, col) = square;
+    KEYS.pieces[piece_type_index(piece_type)][color_index(color)][row * 8 + col]
+}
+
+pub fn side_to_move_key() -> u64 {
+    KEYS.side_to_move
+}
+
+pub fn white_kingside_castle_key() -> u64 {
+    KEYS.white_kingside_castle
+}
+
+pub fn white_queenside_castle_key() -> u64 {
+    KEYS.white_queenside_castle
+}
+
+pub fn black_kingside_castle_key() -> u64 {
+    KEYS.black_kingside_castle
+}
+
+pub fn black_queenside_castle_key() -> u64 {
+    KEYS.black_queenside_castle
+}
+
+pub fn en_passant_file_key(col: usize) -> u64 {
+    KEYS.en_passant_file[col]
+}
+
+fn piece_type_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5
+    }
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1
+    }
+}
+
+// A small, fast PRNG used only to fill the key tables once at startup; it has no need to be
+// cryptographically secure, just well-distributed.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}